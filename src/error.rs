@@ -0,0 +1,60 @@
+//! Typed domain errors, so handlers can return something more useful
+//! than an opaque `ErrorInternalServerError(format!(...))` for every
+//! failure mode.
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use firestore::errors::FirestoreError;
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DomainError {
+    #[error("Firestore error: {0}")]
+    Firestore(#[from] FirestoreError),
+
+    #[error("Task '{task_name}' was not found")]
+    TaskNotFound { task_name: String },
+
+    #[error("User '{user_id}' was not found")]
+    UserNotFound { user_id: String },
+
+    #[error("Invalid speed rating: {0}")]
+    InvalidSpeedRating(i32),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    code: String,
+}
+
+impl DomainError {
+    fn code(&self) -> &'static str {
+        match self {
+            DomainError::Firestore(_) => "firestore_error",
+            DomainError::TaskNotFound { .. } => "task_not_found",
+            DomainError::UserNotFound { .. } => "user_not_found",
+            DomainError::InvalidSpeedRating(_) => "invalid_speed_rating",
+        }
+    }
+}
+
+impl ResponseError for DomainError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DomainError::TaskNotFound { .. } | DomainError::UserNotFound { .. } => {
+                StatusCode::NOT_FOUND
+            }
+            DomainError::InvalidSpeedRating(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            DomainError::Firestore(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        log::error!("{}", self);
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.to_string(),
+            code: self.code().to_string(),
+        })
+    }
+}
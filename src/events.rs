@@ -0,0 +1,224 @@
+//! Append-only event log backing the `tasks`/`users` projections.
+//!
+//! Every `Event` we accept on `/webhook` is first written here as an
+//! immutable `StoredEvent` before the derived Firestore documents are
+//! touched, so the task/user fields these events set can be rebuilt
+//! from this log. A task's `message` and `speed_rating` are written
+//! straight to `tasks` with no event, so they are not in the log.
+
+use chrono::Utc;
+use firestore::*;
+use futures::stream::{BoxStream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::telemetry::timed_firestore_op;
+use crate::{handle_task_change, handle_timer_end, Colors, DomainError, TaskData};
+
+pub type EventId = String;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind")]
+pub enum EventPayload {
+    TaskChange {
+        #[serde(rename = "goalName")]
+        goal_name: String,
+        #[serde(rename = "taskName")]
+        task_name: String,
+        color: String,
+    },
+    TimerEnd,
+}
+
+/// An immutable record of an `Event` as it was received, stored in the
+/// `events` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredEvent {
+    pub id: EventId,
+    pub username: String,
+    pub payload: EventPayload,
+    pub requested_at: firestore::FirestoreTimestamp,
+}
+
+/// Thin wrapper around a `FirestoreDb` for appending to and replaying
+/// the `events` collection.
+pub struct EventStore<'a> {
+    client: &'a FirestoreDb,
+}
+
+impl<'a> EventStore<'a> {
+    pub fn new(client: &'a FirestoreDb) -> Self {
+        Self { client }
+    }
+
+    /// Persists `payload` as a new immutable event and returns its id.
+    pub async fn append_event(
+        &self,
+        username: String,
+        payload: EventPayload,
+    ) -> FirestoreResult<EventId> {
+        let now = Utc::now();
+
+        // Nanosecond timestamps sort the same way they're generated, so
+        // they double as a cheap monotonic id without a counter document.
+        // Firestore only keeps microsecond precision on `requested_at`, so
+        // that field alone can't break ties between events in the same
+        // microsecond: `id` is the actual order/cursor key.
+        let id = format!("{:020}", now.timestamp_nanos_opt().unwrap_or_default());
+
+        let event = StoredEvent {
+            id: id.clone(),
+            username,
+            payload,
+            requested_at: firestore::FirestoreTimestamp(now),
+        };
+
+        timed_firestore_op(
+            "event_append",
+            self.client
+                .fluent()
+                .insert()
+                .into("events")
+                .document_id(&id)
+                .object(&event)
+                .execute::<StoredEvent>(),
+        )
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Looks up a single event by id, returning `Ok(None)` rather than
+    /// panicking when it doesn't exist.
+    pub async fn find_event(&self, id: &str) -> FirestoreResult<Option<StoredEvent>> {
+        timed_firestore_op(
+            "event_find_one",
+            self.client.fluent().select().by_id_in("events").obj().one(id),
+        )
+        .await
+    }
+
+    /// Returns every event newer than `after` (exclusive), oldest first.
+    /// `after` is resolved against the referenced event's `id`, so a
+    /// missing/unknown cursor yields the full log.
+    ///
+    /// Ordered and filtered by `id` rather than `requested_at`: Firestore
+    /// only keeps microsecond precision on timestamps, so two events
+    /// appended in the same microsecond would tie under `requested_at`
+    /// and leave `order_by`/`greater_than` with no deterministic
+    /// tie-break. `id` is nanosecond-resolution, zero-padded, and already
+    /// unique, so it sorts the same way and never ties.
+    pub async fn find_events(&self, after: Option<&str>) -> FirestoreResult<Vec<StoredEvent>> {
+        let cursor = self.resolve_cursor(after).await?;
+
+        let query = self
+            .client
+            .fluent()
+            .select()
+            .from("events")
+            .order_by([(path!(StoredEvent::id), FirestoreQueryDirection::Ascending)]);
+
+        let stream: BoxStream<FirestoreResult<StoredEvent>> = match cursor {
+            Some(id) => {
+                timed_firestore_op(
+                    "event_find_events",
+                    query
+                        .filter(|q| q.field(path!(StoredEvent::id)).greater_than(id.clone()))
+                        .obj()
+                        .stream_query_with_errors(),
+                )
+                .await?
+            }
+            None => {
+                timed_firestore_op("event_find_events", query.obj().stream_query_with_errors())
+                    .await?
+            }
+        };
+
+        stream.try_collect::<Vec<_>>().await
+    }
+
+    /// Returns events for a single user newer than `after` (exclusive),
+    /// oldest first. Filters server-side on `username` rather than
+    /// pulling the whole collection, so the cost stays proportional to
+    /// that user's history rather than the full log. See `find_events`
+    /// for why ordering/cursoring is done on `id`, not `requested_at`.
+    pub async fn find_events_for_user(
+        &self,
+        username: &str,
+        after: Option<&str>,
+    ) -> FirestoreResult<Vec<StoredEvent>> {
+        let cursor = self.resolve_cursor(after).await?;
+
+        let stream: BoxStream<FirestoreResult<StoredEvent>> = timed_firestore_op(
+            "event_find_events_for_user",
+            self.client
+                .fluent()
+                .select()
+                .from("events")
+                .filter(|q| {
+                    q.for_all([
+                        q.field(path!(StoredEvent::username)).eq(username),
+                        cursor
+                            .clone()
+                            .and_then(|id| q.field(path!(StoredEvent::id)).greater_than(id)),
+                    ])
+                })
+                .order_by([(path!(StoredEvent::id), FirestoreQueryDirection::Ascending)])
+                .obj()
+                .stream_query_with_errors(),
+        )
+        .await?;
+
+        stream.try_collect::<Vec<_>>().await
+    }
+
+    /// Resolves an `after` event id to the `id` of the event it
+    /// references, so callers can turn it into a `>` filter.
+    async fn resolve_cursor(&self, after: Option<&str>) -> FirestoreResult<Option<EventId>> {
+        match after {
+            Some(id) => Ok(self.find_event(id).await?.map(|event| event.id)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Applies a single stored event to the `tasks`/`users` projections, via
+/// the same handlers the webhook uses. Shared by `rebuild` and the
+/// background job worker so both stay in sync with one code path.
+pub async fn apply_event(client: &FirestoreDb, event: StoredEvent) -> Result<(), DomainError> {
+    match event.payload {
+        EventPayload::TaskChange {
+            goal_name,
+            task_name,
+            color,
+        } => {
+            handle_task_change(
+                client,
+                goal_name,
+                event.username,
+                TaskData {
+                    text: task_name,
+                    _id: event.id,
+                },
+                Colors { color },
+            )
+            .await?;
+        }
+        EventPayload::TimerEnd => {
+            handle_timer_end(client, event.username).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Replays the full event log, oldest first, reconstructing the current
+/// `tasks`/`users` projections. Events are applied on top of whatever
+/// `tasks`/`users` documents already exist; they are not cleared first.
+pub async fn rebuild(client: &FirestoreDb) -> Result<usize, DomainError> {
+    let events = EventStore::new(client).find_events(None).await?;
+    let replayed = events.len();
+    for event in events {
+        apply_event(client, event).await?;
+    }
+    Ok(replayed)
+}
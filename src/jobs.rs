@@ -0,0 +1,222 @@
+//! Background job queue for processing webhook events.
+//!
+//! `/webhook` no longer runs `handle_task_change`/`handle_timer_end`
+//! synchronously: it appends the event and enqueues a `Job` pointing at
+//! it, then returns 202 immediately. A spawned worker loop polls the
+//! `jobs` collection for due work and applies it, retrying transient
+//! Firestore failures with exponential backoff instead of dropping the
+//! event on a 500.
+
+use std::time::Duration;
+
+use chrono::{Duration as ChronoDuration, Utc};
+use firestore::errors::{BackoffError, FirestoreError};
+use firestore::*;
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::events::{apply_event, EventId, EventStore};
+use crate::telemetry::timed_firestore_op;
+
+const MAX_ATTEMPTS: i32 = 5;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Pending,
+    InProgress,
+    Failed,
+    Completed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: String,
+    pub event_id: EventId,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub next_run_at: firestore::FirestoreTimestamp,
+}
+
+/// Persists a `Job` for `event_id` so the worker loop can pick it up.
+pub async fn enqueue(client: &FirestoreDb, event_id: EventId) -> FirestoreResult<String> {
+    let id = event_id.clone();
+    let job = Job {
+        id: id.clone(),
+        event_id,
+        status: JobStatus::Pending,
+        attempts: 0,
+        next_run_at: firestore::FirestoreTimestamp(Utc::now()),
+    };
+
+    timed_firestore_op(
+        "job_enqueue",
+        client.fluent().insert().into("jobs").document_id(&id).object(&job).execute::<Job>(),
+    )
+    .await?;
+
+    Ok(id)
+}
+
+/// Runs forever, polling for due jobs and applying them. Intended to be
+/// spawned once at startup via `actix_web::rt::spawn`.
+pub async fn run_worker_loop(client: FirestoreDb) {
+    loop {
+        match due_jobs(&client).await {
+            Ok(jobs) => {
+                for job in jobs {
+                    process_job(&client, job).await;
+                }
+            }
+            Err(e) => log::error!("Failed to poll for due jobs: {}", e),
+        }
+
+        actix_web::rt::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn due_jobs(client: &FirestoreDb) -> FirestoreResult<Vec<Job>> {
+    let now = firestore::FirestoreTimestamp(Utc::now());
+
+    let stream: BoxStream<FirestoreResult<Job>> = timed_firestore_op(
+        "job_due_jobs",
+        client
+            .fluent()
+            .select()
+            .from("jobs")
+            .filter(|q| {
+                q.for_all([
+                    q.field(path!(Job::status)).eq("Pending"),
+                    q.field(path!(Job::next_run_at)).less_than_or_equal(now.clone()),
+                ])
+            })
+            .obj()
+            .stream_query_with_errors(),
+    )
+    .await?;
+
+    stream.try_collect::<Vec<_>>().await
+}
+
+/// Transactionally flips a job from `Pending` to `InProgress` before it's
+/// applied, so a crash/restart between a successful Firestore write and
+/// `mark_completed` can't replay a `Pending` job, and a second worker
+/// instance sharing the same queue can't double-claim it. Returns `None`
+/// if the job was no longer `Pending` by the time this worker got to it.
+async fn claim_job(client: &FirestoreDb, job_id: String) -> FirestoreResult<Option<Job>> {
+    timed_firestore_op(
+        "job_claim",
+        client.run_transaction(move |db, transaction| {
+            let job_id = job_id.clone();
+            Box::pin(async move {
+                let job: Option<Job> = db.fluent().select().by_id_in("jobs").obj().one(&job_id).await?;
+
+                let Some(mut job) = job else {
+                    return Ok(None);
+                };
+                if job.status != JobStatus::Pending {
+                    return Ok(None);
+                }
+                job.status = JobStatus::InProgress;
+
+                db.fluent()
+                    .update()
+                    .fields(paths!(Job::{status}))
+                    .in_col("jobs")
+                    .document_id(&job.id)
+                    .object(&job)
+                    .add_to_transaction(transaction)?;
+
+                Ok(Some(job))
+            }) as BoxFuture<'_, Result<Option<Job>, BackoffError<FirestoreError>>>
+        }),
+    )
+    .await
+}
+
+async fn process_job(client: &FirestoreDb, job: Job) {
+    let mut job = match claim_job(client, job.id.clone()).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return, // already claimed or finished elsewhere
+        Err(e) => {
+            log::error!("Failed to claim job {}: {}", job.id, e);
+            return;
+        }
+    };
+
+    let event = match EventStore::new(client).find_event(&job.event_id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => {
+            log::error!("Job {} references missing event {}", job.id, job.event_id);
+            mark_failed(client, &job).await;
+            return;
+        }
+        Err(e) => {
+            log::error!("Failed to load event {} for job {}: {}", job.event_id, job.id, e);
+            reschedule(client, &mut job).await;
+            return;
+        }
+    };
+
+    match apply_event(client, event).await {
+        Ok(()) => mark_completed(client, &job).await,
+        Err(e) => {
+            log::error!("Job {} failed: {}", job.id, e);
+            reschedule(client, &mut job).await;
+        }
+    }
+}
+
+async fn reschedule(client: &FirestoreDb, job: &mut Job) {
+    job.attempts += 1;
+
+    if job.attempts >= MAX_ATTEMPTS {
+        mark_failed(client, job).await;
+        return;
+    }
+
+    // Back to Pending (it was claimed as InProgress) so due_jobs picks it
+    // up again once it's due.
+    job.status = JobStatus::Pending;
+
+    // Exponential backoff: 2^attempts seconds.
+    let backoff = ChronoDuration::seconds(2i64.pow(job.attempts as u32));
+    job.next_run_at = firestore::FirestoreTimestamp(Utc::now() + backoff);
+
+    if let Err(e) = save(client, job).await {
+        log::error!("Failed to reschedule job {}: {}", job.id, e);
+    }
+}
+
+async fn mark_completed(client: &FirestoreDb, job: &Job) {
+    let mut job = job.clone();
+    job.status = JobStatus::Completed;
+    if let Err(e) = save(client, &job).await {
+        log::error!("Failed to mark job {} completed: {}", job.id, e);
+    }
+}
+
+async fn mark_failed(client: &FirestoreDb, job: &Job) {
+    let mut job = job.clone();
+    job.status = JobStatus::Failed;
+    if let Err(e) = save(client, &job).await {
+        log::error!("Failed to mark job {} failed: {}", job.id, e);
+    }
+}
+
+async fn save(client: &FirestoreDb, job: &Job) -> FirestoreResult<()> {
+    timed_firestore_op(
+        "job_save",
+        client
+            .fluent()
+            .update()
+            .fields(paths!(Job::{status, attempts, next_run_at}))
+            .in_col("jobs")
+            .document_id(&job.id)
+            .object(job)
+            .execute::<Job>(),
+    )
+    .await?;
+    Ok(())
+}
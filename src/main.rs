@@ -3,11 +3,27 @@ use serde::{Serialize, Deserialize};
 use firestore::*;
 use chrono::Utc;
 use env_logger;         // to initialize the logger
+use firestore::errors::BackoffError;
+use futures::future::BoxFuture;
 use futures::stream::{BoxStream, TryStreamExt};
 use actix_cors::Cors;  // Add this import
 use std::env;  // Add this for environment variables
 use dotenv::dotenv;
 
+mod events;
+use events::{EventPayload, EventStore};
+
+mod signature;
+use signature::VerifySignature;
+
+mod error;
+use error::DomainError;
+
+mod jobs;
+
+mod telemetry;
+use telemetry::{install_recorder, metrics_handler, timed_firestore_op, RequestMetrics};
+
 
 async fn index() -> impl Responder {
     HttpResponse::Ok().body("Hello, Actix-web!")
@@ -63,9 +79,13 @@ pub struct User {
 }
 
 #[derive(Serialize)]
-pub struct UpdateResponse {
-    pub task: Option<FirestoreTask>,
-    pub user: Option<User>,
+pub struct AcceptedResponse {
+    pub job_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RebuildResponse {
+    pub events_replayed: usize,
 }
 
 #[get("/healthz")]
@@ -80,10 +100,10 @@ pub async fn handle_task_change(
     username: String,
     nexa: TaskData,
     colors: Colors,
-) -> Result<(FirestoreTask, User), actix_web::Error> {
+) -> Result<(FirestoreTask, User), DomainError> {
     let task_name = nexa.text;
     let color = colors.color;
-    
+
     let new_task = FirestoreTask {
         goal_name,
         username: username.clone(),
@@ -94,209 +114,203 @@ pub async fn handle_task_change(
         speed_rating: None,
     };
 
-    // Update the task in Firestore.
-    let task_updated = client.fluent()
+    let new_user = User {
+        id: username.clone(),
+        current_task_id: task_name.clone(),
+        pomodoro_spent: 0,
+    };
+
+    // Upsert the task and user as one atomic unit: if the user write
+    // fails, the task write must not stick around pointing nowhere.
+    let mut transaction = client.begin_transaction().await?;
+
+    client.fluent()
         .update()
         .fields(paths!(FirestoreTask::{goal_name, username, task_name, color, updated_at}))
         .in_col("tasks")
         // Note: using task_name as document_id here per your changes.
         .document_id(&task_name)
         .object(&new_task)
-        .execute::<FirestoreTask>()
-        .await
-        .map_err(|e| {
-            log::error!("Failed to update Firestore task document: {}", e);
-            actix_web::error::ErrorInternalServerError(
-                format!("Failed to update Firestore task document: {}", e)
-            )
-        })?;
+        .add_to_transaction(&mut transaction)?;
 
-    // Update the user in Firestore.
-    let user_updated = client.fluent()
+    client.fluent()
         .update()
         .fields(paths!(User::{id, current_task_id, pomodoro_spent}))
         .in_col("users")
         .document_id(&username)
-        .object(&User {
-            id: username.clone(),
-            current_task_id: task_name.clone(),
-            pomodoro_spent: 0,
-        })
-        .execute::<User>()
-        .await
-        .map_err(|e| {
-            log::error!("Failed to update Firestore user document: {}", e);
-            actix_web::error::ErrorInternalServerError(
-                format!("Failed to update Firestore user document: {}", e)
-            )
-        })?;
+        .object(&new_user)
+        .add_to_transaction(&mut transaction)?;
+
+    timed_firestore_op("task_change_commit", transaction.commit()).await?;
 
-    Ok((task_updated, user_updated))
+    Ok((new_task, new_user))
 }
 
 /// Business logic for handling a TimerEnd event.
 pub async fn handle_timer_end(
     client: &FirestoreDb,
     username: String,
-) -> Result<User, actix_web::Error> {
-    let user: Option<User> = client.fluent()
-        .select()
-        .by_id_in("users")
-        .obj()
-        .one(&username)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to get Firestore user document: {}", e);
-            actix_web::error::ErrorInternalServerError(
-                format!("Failed to get Firestore user document: {}", e)
-            )
-        })?;
-
-    let new_pomodoro_spent = match user {
-        Some(user) => user.pomodoro_spent + 1,
-        None => 0,
-    };
-
-    let user_updated = client.fluent()
-        .update()
-        .fields(paths!(User::{id, pomodoro_spent}))
-        .in_col("users")
-        .document_id(&username)
-        .object(&User {
-            id: username.clone(),
-            current_task_id: "".to_string(),
-            pomodoro_spent: new_pomodoro_spent,
-        })
-        .execute::<User>()
-        .await
-        .map_err(|e| {
-            log::error!("Failed to update Firestore user document: {}", e);
-            actix_web::error::ErrorInternalServerError(
-                format!("Failed to update Firestore user document: {}", e)
-            )
-        })?;
+) -> Result<User, DomainError> {
+    // Read-modify-write on pomodoro_spent races when two workcomplete
+    // events land close together, so the read and the write must happen
+    // inside the same transaction. `FirestoreTransaction` itself is a
+    // write-only accumulator, so the read has to go through the
+    // consistency-scoped `db` handle `run_transaction` hands to the
+    // closure, not the transaction object.
+    let user_updated = timed_firestore_op(
+        "timer_end",
+        client.run_transaction(move |db, transaction| {
+            let username = username.clone();
+            Box::pin(async move {
+                let user: Option<User> = db
+                    .fluent()
+                    .select()
+                    .by_id_in("users")
+                    .obj()
+                    .one(&username)
+                    .await
+                    .map_err(DomainError::from)?;
+
+                let new_pomodoro_spent = match user {
+                    Some(user) => user.pomodoro_spent + 1,
+                    None => 0,
+                };
+
+                let user_updated = User {
+                    id: username.clone(),
+                    current_task_id: "".to_string(),
+                    pomodoro_spent: new_pomodoro_spent,
+                };
+
+                db.fluent()
+                    .update()
+                    .fields(paths!(User::{id, pomodoro_spent}))
+                    .in_col("users")
+                    .document_id(&username)
+                    .object(&user_updated)
+                    .add_to_transaction(transaction)
+                    .map_err(DomainError::from)?;
+
+                Ok(user_updated)
+            }) as BoxFuture<'_, Result<User, BackoffError<DomainError>>>
+        }),
+    )
+    .await?;
 
     Ok(user_updated)
 }
 
-/// Consolidated business logic to process any event.
+/// Persists an incoming `Event` and enqueues a job to process it, rather
+/// than running the Firestore writes inline on the request path.
 pub async fn process_event(
     client: &FirestoreDb,
     event: Event,
-) -> Result<UpdateResponse, actix_web::Error> {
-    let mut response = UpdateResponse {
-        task: None,
-        user: None,
+) -> Result<Option<String>, DomainError> {
+    let (username, payload, event_key) = match event {
+        Event::TaskChange { goal_name, username, nexa, colors } => (
+            username,
+            EventPayload::TaskChange {
+                goal_name,
+                task_name: nexa.text,
+                color: colors.color,
+            },
+            "task_change",
+        ),
+        Event::TimerEnd { username } => (username, EventPayload::TimerEnd, "timer_end"),
+        Event::Other => {
+            metrics::counter!("events_processed_total", "eventKey" => "other").increment(1);
+            return Ok(None);
+        }
     };
+    metrics::counter!("events_processed_total", "eventKey" => event_key).increment(1);
 
-    match event {
-        Event::TaskChange { goal_name, username, nexa, colors } => {
-            let (task, user) = handle_task_change(client, goal_name, username, nexa, colors).await?;
-            response.task = Some(task);
-            response.user = Some(user);
-        }
-        Event::TimerEnd { username } => {
-            let user = handle_timer_end(client, username).await?;
-            response.user = Some(user);
-        }
-        _ => {}
-    }
-    Ok(response)
+    let event_id = EventStore::new(client).append_event(username, payload).await?;
+    let job_id = jobs::enqueue(client, event_id).await?;
+
+    Ok(Some(job_id))
 }
 
 async fn get_user_tasks(
     client: &FirestoreDb,
     user_id: String,
-) -> Result<Vec<FirestoreTask>, actix_web::Error> {
-    let object_stream: BoxStream<FirestoreResult<FirestoreTask>> = client.fluent()
-        .select()
-        .fields(paths!(FirestoreTask::{goal_name, username, task_name, color, updated_at}))
-        .from("tasks")
-        .filter( |q| {
-            q.field(path!(FirestoreTask::username)).eq(user_id.clone())
-        })
-        .order_by([(
-            path!(FirestoreTask::updated_at), FirestoreQueryDirection::Descending
-        )])
-        .obj()
-        .stream_query_with_errors()
-        .await
-        .map_err(|e| {
-            log::error!("Failed to get Firestore task documents: {}", e);
-            actix_web::error::ErrorInternalServerError(
-                format!("Failed to get Firestore task documents: {}", e)
-            )
-        })?;
-
+) -> Result<Vec<FirestoreTask>, DomainError> {
+    let object_stream: BoxStream<FirestoreResult<FirestoreTask>> = timed_firestore_op(
+        "get_user_tasks_query",
+        client.fluent()
+            .select()
+            .fields(paths!(FirestoreTask::{goal_name, username, task_name, color, updated_at}))
+            .from("tasks")
+            .filter( |q| {
+                q.field(path!(FirestoreTask::username)).eq(user_id.clone())
+            })
+            .order_by([(
+                path!(FirestoreTask::updated_at), FirestoreQueryDirection::Descending
+            )])
+            .obj()
+            .stream_query_with_errors(),
+    )
+    .await?;
 
-    let tasks = object_stream.try_collect::<Vec<_>>().await
-        .map_err(|e| {
-            log::error!("Failed to collect Firestore task documents: {}", e);
-            actix_web::error::ErrorInternalServerError(
-                format!("Failed to collect Firestore task documents: {}", e)
-            )
-        })?;
+    let tasks = object_stream.try_collect::<Vec<_>>().await?;
     Ok(tasks)
 }
 
 async fn get_user_current_task(
     client: &FirestoreDb,
     user_id: String,
-) -> Result<Option<FirestoreTask>, actix_web::Error> {
+) -> Result<Option<FirestoreTask>, DomainError> {
     // First get the user to find current_task_id
-    let user: Option<User> = client.fluent()
-        .select()
-        .by_id_in("users")
-        .obj()
-        .one(&user_id)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to get user: {}", e);
-            actix_web::error::ErrorInternalServerError(e.to_string())
-        })?;
-    
-    // If no user or no current task, return None
+    let user: Option<User> = timed_firestore_op(
+        "get_user_current_task_user",
+        client.fluent().select().by_id_in("users").obj().one(&user_id),
+    )
+    .await?;
+
     let Some(user) = user else {
-        return Ok(None);
+        return Err(DomainError::UserNotFound { user_id });
     };
+    // No current task is a legitimate state, not an error.
     if user.current_task_id.is_empty() {
         return Ok(None);
     }
 
     // Get the current task
-    let task = client.fluent()
-        .select()
-        .by_id_in("tasks")
-        .obj()
-        .one(&user.current_task_id)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to get task: {}", e);
-            actix_web::error::ErrorInternalServerError(e.to_string())
-        })?;
+    let task = timed_firestore_op(
+        "get_user_current_task_task",
+        client.fluent().select().by_id_in("tasks").obj().one(&user.current_task_id),
+    )
+    .await?;
 
     Ok(task)
 }
 
+async fn get_user_events(
+    client: &FirestoreDb,
+    user_id: String,
+    after: Option<String>,
+) -> Result<Vec<events::StoredEvent>, DomainError> {
+    Ok(EventStore::new(client)
+        .find_events_for_user(&user_id, after.as_deref())
+        .await?)
+}
+
 async fn update_speed_rating(
     client: &FirestoreDb,
     task_name: String,
     speed_rating: i32,
-) -> Result<FirestoreTask, actix_web::Error> {
-
-    let find_task: Option<FirestoreTask> = client.fluent()
-        .select()
-        .by_id_in("tasks")
-        .obj()
-        .one(&task_name)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to get task: {}", e);
-            actix_web::error::ErrorInternalServerError(e.to_string())
-        })?;
+) -> Result<FirestoreTask, DomainError> {
+    if !(1..=5).contains(&speed_rating) {
+        return Err(DomainError::InvalidSpeedRating(speed_rating));
+    }
+
+    let find_task: Option<FirestoreTask> = timed_firestore_op(
+        "update_speed_rating_find_task",
+        client.fluent().select().by_id_in("tasks").obj().one(&task_name),
+    )
+    .await?;
 
     if find_task.is_none() {
-        return Err(actix_web::error::ErrorNotFound("Task not found"));
+        return Err(DomainError::TaskNotFound { task_name });
     }
 
     let task = FirestoreTask {
@@ -309,20 +323,17 @@ async fn update_speed_rating(
         speed_rating: Some(speed_rating),
     };
 
-    let task_updated = client.fluent()
-        .update()
-        .fields(paths!(FirestoreTask::{updated_at, speed_rating}))
-        .in_col("tasks")
-        .document_id(&task_name)
-        .object(&task)
-        .execute::<FirestoreTask>()
-        .await
-        .map_err(|e| {
-            log::error!("Failed to update Firestore task document: {}", e);
-            actix_web::error::ErrorInternalServerError(
-                format!("Failed to update Firestore task document: {}", e)
-            )
-        })?;
+    let task_updated = timed_firestore_op(
+        "update_speed_rating_update",
+        client.fluent()
+            .update()
+            .fields(paths!(FirestoreTask::{updated_at, speed_rating}))
+            .in_col("tasks")
+            .document_id(&task_name)
+            .object(&task)
+            .execute::<FirestoreTask>(),
+    )
+    .await?;
 
     Ok(task_updated)
 }
@@ -332,21 +343,16 @@ async fn update_message(
     user_id: String,
     task_name: String,
     message: String,
-) -> Result<FirestoreTask, actix_web::Error> {
+) -> Result<FirestoreTask, DomainError> {
     // First check if the task exists
-    let find_task: Option<FirestoreTask> = client.fluent()
-        .select()
-        .by_id_in("tasks")
-        .obj()
-        .one(&task_name)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to get task: {}", e);
-            actix_web::error::ErrorInternalServerError(e.to_string())
-        })?;
+    let find_task: Option<FirestoreTask> = timed_firestore_op(
+        "update_message_find_task",
+        client.fluent().select().by_id_in("tasks").obj().one(&task_name),
+    )
+    .await?;
 
     if find_task.is_none() {
-        return Err(actix_web::error::ErrorNotFound("Task not found"));
+        return Err(DomainError::TaskNotFound { task_name });
     }
 
     let new_task: FirestoreTask = FirestoreTask {
@@ -359,20 +365,17 @@ async fn update_message(
         speed_rating: None,
     };
 
-    let task_updated = client.fluent()
-        .update()
-        .fields(paths!(FirestoreTask::{updated_at, message}))
-        .in_col("tasks")
-        .document_id(&task_name)
-        .object(&new_task)
-        .execute::<FirestoreTask>()
-        .await
-        .map_err(|e| {
-            log::error!("Failed to update Firestore task document: {}", e);
-            actix_web::error::ErrorInternalServerError(
-                format!("Failed to update Firestore task document: {}", e)
-            )
-        })?;
+    let task_updated = timed_firestore_op(
+        "update_message_update",
+        client.fluent()
+            .update()
+            .fields(paths!(FirestoreTask::{updated_at, message}))
+            .in_col("tasks")
+            .document_id(&task_name)
+            .object(&new_task)
+            .execute::<FirestoreTask>(),
+    )
+    .await?;
 
     Ok(task_updated)
 }
@@ -384,8 +387,8 @@ async fn process_update(
     payload: web::Json<Event>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let event = payload.into_inner();
-    let response = process_event(&client, event).await?;
-    Ok(HttpResponse::Ok().json(response))
+    let job_id = process_event(&client, event).await?;
+    Ok(HttpResponse::Accepted().json(AcceptedResponse { job_id }))
 }
 
 #[post("/users/{userId}/tasks/{taskName}/speedRating")]
@@ -432,11 +435,38 @@ async fn get_user_current_task_handler(
     Ok(HttpResponse::Ok().json(task))
 }
 
+#[derive(Deserialize)]
+struct EventsQuery {
+    after: Option<String>,
+}
+
+#[get("/users/{userId}/events")]
+async fn get_user_events_handler(
+    client: web::Data<FirestoreDb>,
+    path: web::Path<String>,
+    query: web::Query<EventsQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = path.into_inner();
+    let events = get_user_events(&client, user_id, query.into_inner().after).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
+
+/// Replays the `events` collection to reconstruct the `tasks`/`users`
+/// projections, so a corrupted or lost read model can be recovered.
+#[post("/admin/rebuild")]
+async fn rebuild_handler(
+    client: web::Data<FirestoreDb>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let events_replayed = events::rebuild(&client).await?;
+    Ok(HttpResponse::Ok().json(RebuildResponse { events_replayed }))
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok(); // Load .env file
     env_logger::init();
-    
+    let metrics_handle = install_recorder();
+
     // You can set these via environment variables
     let frontend_origin = env::var("FRONTEND_ORIGIN")
         .unwrap_or_else(|_| "http://localhost:3000".to_string());
@@ -450,6 +480,10 @@ async fn main() -> std::io::Result<()> {
 
     let client_data = web::Data::new(firestore_client);
 
+    // The worker owns its own cheap clone of the Firestore client and
+    // polls the `jobs` collection independently of the HTTP workers.
+    actix_web::rt::spawn(jobs::run_worker_loop(client_data.as_ref().clone()));
+
     HttpServer::new(move || {
         // Create CORS middleware
         let cors = Cors::default()
@@ -467,14 +501,25 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             .wrap(cors)  // Add the CORS middleware
+            .wrap(RequestMetrics)
             .app_data(client_data.clone())
+            .app_data(web::Data::new(metrics_handle.clone()))
             .route("/", web::get().to(index))
-            .service(process_update)
             .service(healthz)
+            .service(metrics_handler)
             .service(get_user_tasks_handler)
             .service(get_user_current_task_handler)
             .service(update_speed_rating_handler)
             .service(update_message_handler)
+            .service(get_user_events_handler)
+            .service(rebuild_handler)
+            // An empty-prefix scope matches every path, so it is registered
+            // last: the routes above are matched before the signature check.
+            .service(
+                web::scope("")
+                    .wrap(VerifySignature)
+                    .service(process_update),
+            )
     })
     .bind("127.0.0.1:8000")?
     .run()
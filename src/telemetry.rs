@@ -0,0 +1,115 @@
+//! Observability: a Prometheus text-format `/metrics` endpoint, a
+//! request-instrumentation middleware, and a small helper for timing
+//! Firestore calls.
+
+use std::future::{ready, Future, Ready};
+use std::rc::Rc;
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{get, Error, HttpResponse};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+/// Installs the global metrics recorder and returns a handle that can
+/// render the current state in Prometheus text format.
+pub fn install_recorder() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .install_recorder()
+        .expect("Failed to install Prometheus metrics recorder")
+}
+
+#[get("/metrics")]
+pub async fn metrics_handler(handle: actix_web::web::Data<PrometheusHandle>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(handle.render())
+}
+
+/// Actix middleware that records per-route request count/latency and an
+/// in-flight request gauge, without touching individual handler bodies.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = std::pin::Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let method = req.method().to_string();
+        let path = req
+            .match_pattern()
+            .unwrap_or_else(|| req.path().to_string());
+
+        Box::pin(async move {
+            metrics::gauge!("http_requests_in_flight").increment(1.0);
+            let start = Instant::now();
+
+            let result = service.call(req).await;
+
+            metrics::gauge!("http_requests_in_flight").decrement(1.0);
+            let status = match &result {
+                Ok(res) => res.status().as_u16(),
+                Err(e) => e.as_response_error().status_code().as_u16(),
+            };
+
+            let labels = [
+                ("method", method),
+                ("path", path),
+                ("status", status.to_string()),
+            ];
+            metrics::counter!("http_requests_total", &labels).increment(1);
+            metrics::histogram!("http_request_duration_seconds", &labels)
+                .record(start.elapsed().as_secs_f64());
+
+            result
+        })
+    }
+}
+
+/// Times a Firestore call, recording its latency and, on failure, an
+/// error counter, both labeled by `op`.
+pub async fn timed_firestore_op<T, E>(
+    op: &'static str,
+    fut: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = fut.await;
+
+    metrics::histogram!("firestore_operation_duration_seconds", "op" => op)
+        .record(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        metrics::counter!("firestore_operation_errors_total", "op" => op).increment(1);
+    }
+
+    result
+}